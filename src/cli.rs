@@ -5,30 +5,119 @@ use autocomplete::*;
 use cli_property::*;
 use cli_command::*;
 
+/// Reserved input that requests the command tree instead of being matched as a command.
+pub const HELP_COMMAND: &'static str = "help";
+
+/// Short description and usage hint registered alongside a command or property, surfaced by the
+/// help tree and by a trailing `--help`/`-h` token.
+pub struct CommandHelp<'h> {
+	pub description: &'h str,
+	pub usage: &'h str
+}
+
+/// Verb/syntax style `run_property`/`run_property_with_help` match their `get`/`set` commands
+/// against. This only controls how the command text is matched; the `style` field a caller sees
+/// on `PropertyContextCommon` is unrelated and unaffected by this choice.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PropertyMatchStyle {
+	/// `id/get` to read, `id/set value` to write — the only form this crate supported before
+	/// configurable styles.
+	DelimitedGetSet,
+	/// `prop get id` to read, `prop set id value` to write.
+	VerbFirst,
+	/// `id` alone to read, `id = value` to write.
+	Assignment
+}
+
 /// Helper for matching commands and properties against an input line.
 pub struct CliExecutor<'a> {
 	matcher: CliLineMatcher<'a>,
-	terminal: &'a mut CharacterTerminalWriter
+	terminal: &'a mut CharacterTerminalWriter,
+	property_style: PropertyMatchStyle,
+	dumping_properties: bool,
+	parse_error: bool
 }
 
 impl<'a> CliExecutor<'a> {
 	pub fn new<T: CharacterTerminalWriter>(matcher: CliLineMatcher<'a>, terminal: &'a mut T) -> Self {
 		CliExecutor {
 			matcher: matcher,
-			terminal: terminal
+			terminal: terminal,
+			property_style: PropertyMatchStyle::DelimitedGetSet,
+			dumping_properties: false,
+			parse_error: false
 		}
 	}
 
-	/// Finish the execution of this line invocation.
-	pub fn close(self) -> CliLineMatcher<'a> {
+	/// Overrides the verb/syntax style used to match property commands for every subsequent
+	/// `run_property`/`run_property_with_help` call on this executor (defaults to
+	/// `PropertyMatchStyle::DelimitedGetSet`).
+	pub fn set_property_style(&mut self, style: PropertyMatchStyle) {
+		self.property_style = style;
+	}
+
+	/// Builds an executor that, instead of matching, invokes the getter for every property
+	/// announced through `run_property`/`run_property_with_help`, so a single pass over a device's
+	/// command set prints a full property listing.
+	pub fn dump_properties<T: CharacterTerminalWriter>(matcher: CliLineMatcher<'a>, terminal: &'a mut T) -> Self {
+		CliExecutor {
+			matcher: matcher,
+			terminal: terminal,
+			property_style: PropertyMatchStyle::DelimitedGetSet,
+			dumping_properties: true,
+			parse_error: false
+		}
+	}
+
+	/// Finish the execution of this line invocation. If nothing matched, prints up to two "did you
+	/// mean" suggestions for commands that are close (by edit distance) to the typed token.
+	pub fn close(mut self) -> CliLineMatcher<'a> {
+		if !self.matcher.has_match() {
+			self.suggest();
+		}
+
 		self.matcher
 	}
 
+	/// Looks for announced commands whose first token is a close edit-distance match to the typed
+	/// token, and prints the closest one or two as suggestions.
+	fn suggest(&mut self) {
+		let typed = match self.matcher.typed_token() {
+			Some(t) if !t.is_empty() => t,
+			_ => return
+		};
+
+		let threshold = suggestion_threshold(typed.chars().count());
+		let mut matches: Vec<(&str, usize)> = Vec::new();
+
+		for candidate in self.matcher.candidate_tokens() {
+			if candidate == typed {
+				continue;
+			}
+
+			let distance = levenshtein(typed, candidate);
+			if distance <= threshold {
+				matches.push((candidate, distance));
+			}
+		}
+
+		matches.sort_by_key(|&(candidate, distance)| (distance, candidate));
+		matches.dedup_by(|a, b| a.0 == b.0);
+
+		for &(candidate, _) in matches.iter().take(2) {
+			self.terminal.print_line(&format!("Did you mean: {}?", candidate));
+		}
+	}
+
 	/// Creates a new prefixed execution context, but only if the current line matches. Reduces the
 	/// processing overhead for large tree command environments.
 	pub fn with_prefix<'b, I: Into<Cow<'b, str>>>(&'b mut self, prefix: I) -> Option<PrefixedExecutor<'a, 'b>> {
 		let prefix = prefix.into();
-		if self.matcher.starts_with(&prefix) {
+
+		// While collecting for the help tree, or dumping every property's current value, every
+		// prefix must be entered regardless of what `starts_with` would otherwise say, so nested
+		// commands/properties are captured.
+		if self.matcher.is_collecting() || self.dumping_properties || self.matcher.starts_with(&prefix) {
 			let p = PrefixedExecutor {
 				prefix: prefix,
 				executor: self
@@ -65,22 +154,142 @@ impl<'a> CliExecutor<'a> {
 
 		None
 	}
-	
+
+	/// Like `run_command`, but also registers a `description`/`usage` hint that shows up in the
+	/// help tree, and lets a trailing `--help`/`-h` token print the usage instead of running the
+	/// handler.
+	pub fn run_command_with_help<'b>(&'b mut self, cmd: &str, description: &str, usage: &str) -> Option<CommandContext<'b>> {
+		let help = CommandHelp { description: description, usage: usage };
+
+		match self.matcher.match_cmd_str(cmd, Some(&help)) {
+			LineMatcherProgress::HelpRequested => {
+				self.terminal.print_line(&format!("{} - {}", cmd, description));
+				self.terminal.print_line(&format!("usage: {}", usage));
+				None
+			},
+			LineMatcherProgress::MatchFound => {
+				let args = if let &LineBufferResult::Match { ref args, .. } = self.matcher.get_state() {
+					Some(args.clone())
+				} else {
+					None
+				};
+
+				if let Some(args) = args {
+					let ctx = CommandContext {
+						args: args.into(),
+						terminal: self.terminal,
+						current_path: ""
+					};
+
+					return Some(ctx);
+				}
+
+				None
+			},
+			_ => None
+		}
+	}
+
 	/// Announces a property that can be manipulated. Returns an execution context in case the property
 	/// is to be either retrieved or updated.
 	pub fn run_property<'b, V, P, Id: Into<Cow<'b, str>>>(&'b mut self, property_id: Id, input_parser: P) -> Option<PropertyContext<'b, V>> where P: ValueInput<V>, V: Display {
 		let property_id: Cow<str> = property_id.into();
 
-		if self.matcher.match_cmd_str(&format!("{}/get", property_id), None) == LineMatcherProgress::MatchFound {
+		if self.dumping_properties {
+			return Some(PropertyContext::Get(PropertyContextGet {
+				common: PropertyContextCommon {
+					args: "".into(),
+					terminal: self.terminal,
+					current_path: "",
+					id: property_id,
+					style: PropertyCommandStyle::DelimitedGetSet
+				}
+			}));
+		}
+
+		let (get_cmd, set_cmd) = self.property_match_strings(&property_id);
+
+		if self.matcher.match_cmd_str(&get_cmd, None) == LineMatcherProgress::MatchFound {
 			let args = if let &LineBufferResult::Match { ref args, .. } = self.matcher.get_state() {
 				args.clone()
 			} else {
 				"".into()
 			};
 
+			// For `Assignment` style, `get_cmd` (`id`) is a prefix of `set_cmd` (`id =`), so a
+			// `set` line like `id = 5` matches here too, with `= 5` left over as args. Only treat
+			// this as a `get` if it isn't actually an assignment in disguise.
+			if !is_assignment_shadow(self.property_style, &args) {
+				return Some(PropertyContext::Get(PropertyContextGet {
+					common: PropertyContextCommon {
+						args: args.into(),
+						terminal: self.terminal,
+						current_path: "",
+						id: property_id,
+						style: PropertyCommandStyle::DelimitedGetSet
+					}
+				}));
+			}
+		}
+
+		if self.matcher.match_cmd_str(&set_cmd, None) == LineMatcherProgress::MatchFound {
+			let args = if let &LineBufferResult::Match { ref args, .. } = self.matcher.get_state() {
+				args.trim()
+			} else {
+				"".into()
+			};
+
+			match input_parser.input(&args) {
+				Ok(val) => {
+					return Some(PropertyContext::Set(PropertyContextSet {
+						common: PropertyContextCommon {
+							args: args.into(),
+							terminal: self.terminal,
+							current_path: "",
+							id: property_id,
+							style: PropertyCommandStyle::DelimitedGetSet
+						},
+						value: val
+					}));
+				},
+				Err(e) => {
+					self.parse_error = true;
+					self.terminal.print_line(&format!("Couldn't parse the value: {}", e));
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Returns true if a `run_property`/`run_property_with_help` call on this executor failed to
+	/// parse a `set` value.
+	pub fn had_parse_error(&self) -> bool {
+		self.parse_error
+	}
+
+	/// Builds the `get`/`set` command strings to match for `property_id`, in the style configured
+	/// with `set_property_style`.
+	fn property_match_strings(&self, property_id: &str) -> (String, String) {
+		match self.property_style {
+			PropertyMatchStyle::DelimitedGetSet => (format!("{}/get", property_id), format!("{}/set", property_id)),
+			PropertyMatchStyle::VerbFirst => (format!("prop get {}", property_id), format!("prop set {}", property_id)),
+			PropertyMatchStyle::Assignment => (property_id.to_string(), format!("{} =", property_id))
+		}
+	}
+
+	/// Like `run_property`, but also registers a `description`/`usage` hint that shows up in the
+	/// help tree, and lets a trailing `--help`/`-h` token on the `get` form print the usage instead
+	/// of running the handler. The hint is only registered once per property (against `get_cmd`),
+	/// so the help tree doesn't print it twice for the `get`/`set` pair.
+	pub fn run_property_with_help<'b, V, P, Id: Into<Cow<'b, str>>>(&'b mut self, property_id: Id, input_parser: P, description: &str, usage: &str) -> Option<PropertyContext<'b, V>> where P: ValueInput<V>, V: Display {
+		let property_id: Cow<str> = property_id.into();
+		let help = CommandHelp { description: description, usage: usage };
+
+		if self.dumping_properties {
 			return Some(PropertyContext::Get(PropertyContextGet {
 				common: PropertyContextCommon {
-					args: args.into(),
+					args: "".into(),
 					terminal: self.terminal,
 					current_path: "",
 					id: property_id,
@@ -89,7 +298,44 @@ impl<'a> CliExecutor<'a> {
 			}));
 		}
 
-		if self.matcher.match_cmd_str(&format!("{}/set", property_id), None) == LineMatcherProgress::MatchFound {
+		let (get_cmd, set_cmd) = self.property_match_strings(&property_id);
+
+		match self.matcher.match_cmd_str(&get_cmd, Some(&help)) {
+			LineMatcherProgress::HelpRequested => {
+				self.terminal.print_line(&format!("{} - {}", property_id, description));
+				self.terminal.print_line(&format!("usage: {}", usage));
+				return None;
+			},
+			LineMatcherProgress::MatchFound => {
+				let args = if let &LineBufferResult::Match { ref args, .. } = self.matcher.get_state() {
+					args.clone()
+				} else {
+					"".into()
+				};
+
+				// For `Assignment` style, `get_cmd` (`id`) is a prefix of `set_cmd` (`id =`), so a
+				// `set` line like `id = 5` matches here too, with `= 5` left over as args. Only
+				// treat this as a `get` if it isn't actually an assignment in disguise.
+				if !is_assignment_shadow(self.property_style, &args) {
+					return Some(PropertyContext::Get(PropertyContextGet {
+						common: PropertyContextCommon {
+							args: args.into(),
+							terminal: self.terminal,
+							current_path: "",
+							id: property_id,
+							style: PropertyCommandStyle::DelimitedGetSet
+						}
+					}));
+				}
+			},
+			_ => {}
+		}
+
+		// `help` was already registered once against `get_cmd` above; pass `None` here so the
+		// property's description/usage isn't recorded (and printed) twice in the help tree. That
+		// also means a trailing `--help`/`-h` only works on the `get` form: `set_cmd` is matched
+		// without a help hint, so `id/set --help` runs the value parser like any other argument.
+		if self.matcher.match_cmd_str(&set_cmd, None) == LineMatcherProgress::MatchFound {
 			let args = if let &LineBufferResult::Match { ref args, .. } = self.matcher.get_state() {
 				args.trim()
 			} else {
@@ -110,6 +356,7 @@ impl<'a> CliExecutor<'a> {
 					}));
 				},
 				Err(e) => {
+					self.parse_error = true;
 					self.terminal.print_line(&format!("Couldn't parse the value: {}", e));
 				}
 			}
@@ -122,6 +369,66 @@ impl<'a> CliExecutor<'a> {
 	pub fn get_terminal(&mut self) -> &mut CharacterTerminalWriter {
 		self.terminal
 	}
+
+	/// Returns true if `line` should be treated as a request for the command tree rather than
+	/// matched as a command: an empty line, or the reserved word `help`.
+	pub fn is_help_request(line: &str) -> bool {
+		let trimmed = line.trim();
+		trimmed.is_empty() || trimmed == HELP_COMMAND
+	}
+
+	/// Builds an executor that records every `run_command`/`run_property`/`with_prefix` call
+	/// instead of matching it against an input line, so the full set of announced commands can
+	/// be printed back as a tree by `help()`. Pair with `is_help_request` to decide when to build
+	/// one of these instead of a regular `CliExecutor`.
+	pub fn discover<T: CharacterTerminalWriter>(matcher: CliLineMatcher<'a>, terminal: &'a mut T) -> Self {
+		let mut matcher = matcher;
+		matcher.begin_collecting();
+
+		CliExecutor {
+			matcher: matcher,
+			terminal: terminal,
+			property_style: PropertyMatchStyle::DelimitedGetSet,
+			dumping_properties: false,
+			parse_error: false
+		}
+	}
+
+	/// Prints every command and property path recorded since this executor was built with
+	/// `discover()`, as a tree: each path is split on `/`, and a segment shared with the
+	/// previous entry is only printed once, as the parent of the segments that follow it.
+	pub fn help(self) -> CliLineMatcher<'a> {
+		let mut stack: Vec<&str> = Vec::new();
+
+		for entry in self.matcher.collected() {
+			let segments: Vec<&str> = entry.name().split('/').collect();
+
+			let mut shared = 0;
+			while shared < stack.len() && shared + 1 < segments.len() && stack[shared] == segments[shared] {
+				shared += 1;
+			}
+			stack.truncate(shared);
+
+			for &segment in &segments[shared..segments.len() - 1] {
+				self.terminal.print_line(&format!("{}{}", "  ".repeat(stack.len()), segment));
+				stack.push(segment);
+			}
+
+			let label = segments[segments.len() - 1];
+			let depth = stack.len();
+
+			match entry.description() {
+				Some(description) => self.terminal.print_line(&format!("{}{} - {}", "  ".repeat(depth), label, description)),
+				None => self.terminal.print_line(&format!("{}{}", "  ".repeat(depth), label))
+			}
+
+			if let Some(usage) = entry.usage() {
+				self.terminal.print_line(&format!("{}usage: {}", "  ".repeat(depth + 1), usage));
+			}
+		}
+
+		self.matcher
+	}
 }
 
 impl<'a> Deref for CliExecutor<'a> {
@@ -132,6 +439,38 @@ impl<'a> Deref for CliExecutor<'a> {
     }
 }
 
+/// Runs every line of a script through a fresh `CliExecutor`, using `handler` to announce the same
+/// commands/properties that would be announced for a single interactive line. Blank lines and lines
+/// starting with `#` are skipped. Lets a device replay a startup or provisioning script without the
+/// caller re-implementing the line loop; takes an iterator of lines rather than a path so it stays
+/// usable without a filesystem.
+///
+/// If `stop_on_error` is true, the first line whose `set` value fails to parse stops the script;
+/// otherwise the remaining lines still run. An unrecognised command doesn't stop the script either
+/// way — it's reported like any other unmatched line (see `close`'s suggestions).
+pub fn run_script<'t, T, L, F>(lines: L, terminal: &'t mut T, mut handler: F, stop_on_error: bool) where
+	T: CharacterTerminalWriter,
+	L: IntoIterator<Item = &'t str>,
+	F: FnMut(&mut CliExecutor)
+{
+	for line in lines {
+		let trimmed = line.trim();
+		if trimmed.is_empty() || trimmed.starts_with('#') {
+			continue;
+		}
+
+		let matcher = CliLineMatcher::new(trimmed);
+		let mut executor = CliExecutor::new(matcher, &mut *terminal);
+		handler(&mut executor);
+		let parse_error = executor.had_parse_error();
+		executor.close();
+
+		if stop_on_error && parse_error {
+			break;
+		}
+	}
+}
+
 pub struct PrefixedExecutor<'a: 'p, 'p> {
 	prefix: Cow<'p, str>,
 	executor: &'p mut CliExecutor<'a>
@@ -154,4 +493,191 @@ impl<'a, 'p> PrefixedExecutor<'a, 'p> {
 
 		self.executor.run_property(property_id, input_parser)
 	}
+
+	pub fn run_command_with_help<'b>(&'b mut self, cmd: &str, description: &str, usage: &str) -> Option<CommandContext<'b>> {
+		let cmd = self.add_prefix(cmd);
+
+		self.executor.run_command_with_help(&cmd, description, usage)
+	}
+
+	pub fn run_property_with_help<'b, V, P, Id: Into<Cow<'b, str>>>(&'b mut self, property_id: Id, input_parser: P, description: &str, usage: &str) -> Option<PropertyContext<'b, V>> where P: ValueInput<V>, V: Display {
+		let property_id: Cow<str> = property_id.into();
+		let property_id = self.add_prefix(&property_id);
+
+		self.executor.run_property_with_help(property_id, input_parser, description, usage)
+	}
+}
+
+/// True if a successful match against the bare `Assignment`-style get form (`id`) is actually a
+/// `set` (`id = value`) in disguise, since `id` is a prefix of `id =`.
+fn is_assignment_shadow(style: PropertyMatchStyle, args: &str) -> bool {
+	style == PropertyMatchStyle::Assignment && args.trim_start().starts_with('=')
+}
+
+/// How far off a typed token may be from a candidate before it's no longer worth suggesting:
+/// within 2 edits, or within a third of its own length for longer tokens.
+fn suggestion_threshold(len: usize) -> usize {
+	let scaled = (len + 2) / 3;
+
+	if scaled > 2 { scaled } else { 2 }
+}
+
+/// Levenshtein edit distance between `a` and `b`, computed with the standard single-row DP so it
+/// needs no more than `O(b.len())` extra space.
+fn levenshtein(a: &str, b: &str) -> usize {
+	let b_len = b.chars().count();
+	let mut row: Vec<usize> = (0..=b_len).collect();
+
+	for (i, a_ch) in a.chars().enumerate() {
+		let mut prev_diag = row[0];
+		row[0] = i + 1;
+
+		for (j, b_ch) in b.chars().enumerate() {
+			let tmp = row[j + 1];
+			row[j + 1] = min3(row[j + 1] + 1, row[j] + 1, prev_diag + if a_ch == b_ch { 0 } else { 1 });
+			prev_diag = tmp;
+		}
+	}
+
+	row[b_len]
+}
+
+fn min3(a: usize, b: usize, c: usize) -> usize {
+	if a < b {
+		if a < c { a } else { c }
+	} else {
+		if b < c { b } else { c }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct RecordingTerminal {
+		lines: Vec<String>
+	}
+
+	impl CharacterTerminalWriter for RecordingTerminal {
+		fn print_line(&mut self, line: &str) {
+			self.lines.push(line.into());
+		}
+	}
+
+	#[test]
+	fn prefixed_command_appears_in_help_tree() {
+		let mut terminal = RecordingTerminal { lines: Vec::new() };
+		let matcher = CliLineMatcher::new(HELP_COMMAND);
+		let mut executor = CliExecutor::discover(matcher, &mut terminal);
+
+		if let Some(mut motor) = executor.with_prefix("motor/") {
+			motor.run_command("stop");
+		}
+
+		executor.help();
+
+		assert!(terminal.lines.iter().any(|line| line.contains("motor")));
+		assert!(terminal.lines.iter().any(|line| line.contains("stop")));
+	}
+
+	#[test]
+	fn levenshtein_distances() {
+		assert_eq!(levenshtein("", ""), 0);
+		assert_eq!(levenshtein("speed", "speed"), 0);
+		assert_eq!(levenshtein("", "speed"), 5);
+		assert_eq!(levenshtein("speed", ""), 5);
+		assert_eq!(levenshtein("speed", "sped"), 1);
+		assert_eq!(levenshtein("kitten", "sitting"), 3);
+	}
+
+	#[test]
+	fn suggestion_threshold_boundaries() {
+		assert_eq!(suggestion_threshold(0), 2);
+		assert_eq!(suggestion_threshold(1), 2);
+		assert_eq!(suggestion_threshold(6), 2);
+		assert_eq!(suggestion_threshold(7), 3);
+		assert_eq!(suggestion_threshold(9), 3);
+		assert_eq!(suggestion_threshold(10), 4);
+	}
+
+	fn match_strings_for(style: PropertyMatchStyle) -> (String, String) {
+		let mut terminal = RecordingTerminal { lines: Vec::new() };
+		let matcher = CliLineMatcher::new("speed");
+		let mut executor = CliExecutor::new(matcher, &mut terminal);
+		executor.set_property_style(style);
+
+		executor.property_match_strings("speed")
+	}
+
+	#[test]
+	fn property_match_strings_delimited_get_set() {
+		let (get_cmd, set_cmd) = match_strings_for(PropertyMatchStyle::DelimitedGetSet);
+		assert_eq!(get_cmd, "speed/get");
+		assert_eq!(set_cmd, "speed/set");
+	}
+
+	#[test]
+	fn property_match_strings_verb_first() {
+		let (get_cmd, set_cmd) = match_strings_for(PropertyMatchStyle::VerbFirst);
+		assert_eq!(get_cmd, "prop get speed");
+		assert_eq!(set_cmd, "prop set speed");
+	}
+
+	#[test]
+	fn property_match_strings_assignment() {
+		let (get_cmd, set_cmd) = match_strings_for(PropertyMatchStyle::Assignment);
+		assert_eq!(get_cmd, "speed");
+		assert_eq!(set_cmd, "speed =");
+	}
+
+	struct AlwaysErr;
+
+	impl ValueInput<i32> for AlwaysErr {
+		fn input(&self, _input: &str) -> Result<i32, String> {
+			Err("not a number".into())
+		}
+	}
+
+	#[test]
+	fn run_script_skips_blank_and_comment_lines() {
+		let mut terminal = RecordingTerminal { lines: Vec::new() };
+		let mut matched = 0;
+		let mut run_count = 0;
+
+		run_script(vec!["", "   ", "# a comment", "speed/get"], &mut terminal, |executor| {
+			run_count += 1;
+			if executor.run_command("speed/get").is_some() {
+				matched += 1;
+			}
+		}, false);
+
+		assert_eq!(run_count, 1);
+		assert_eq!(matched, 1);
+	}
+
+	#[test]
+	fn run_script_stops_on_parse_error_when_requested() {
+		let mut terminal = RecordingTerminal { lines: Vec::new() };
+		let mut run_count = 0;
+
+		run_script(vec!["speed/set nope", "speed/get"], &mut terminal, |executor| {
+			run_count += 1;
+			executor.run_property::<i32, _, _>("speed", AlwaysErr);
+		}, true);
+
+		assert_eq!(run_count, 1);
+	}
+
+	#[test]
+	fn run_script_continues_past_parse_error_when_not_requested() {
+		let mut terminal = RecordingTerminal { lines: Vec::new() };
+		let mut run_count = 0;
+
+		run_script(vec!["speed/set nope", "speed/get"], &mut terminal, |executor| {
+			run_count += 1;
+			executor.run_property::<i32, _, _>("speed", AlwaysErr);
+		}, false);
+
+		assert_eq!(run_count, 2);
+	}
 }
\ No newline at end of file